@@ -0,0 +1,62 @@
+#[macro_use]
+extern crate trace_error;
+
+use std::error::Error;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::fs::File;
+
+use trace_error::boxed::BoxedTrace;
+use trace_error::backtrace::JsonBacktraceFmt;
+
+#[derive(Debug)]
+pub struct ConfigError;
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(f, "invalid config")
+    }
+}
+
+impl Error for ConfigError {
+    fn description(&self) -> &str {
+        "invalid config"
+    }
+}
+
+// Unlike `Trace<E>`, `BoxedTrace` can hold either of these unrelated error types in the
+// same `Result`, via `try_box!`.
+fn open_config() -> Result<File, BoxedTrace> {
+    let file = try_box!(File::open("config.toml"));
+    Ok(file)
+}
+
+fn parse_config() -> Result<File, BoxedTrace> {
+    if true {
+        return try_box!(Err(ConfigError));
+    }
+
+    unreachable!()
+}
+
+fn load() -> Result<(), BoxedTrace> {
+    // try_box_context! attaches a message to whichever error type propagates through here
+    try_box_context!(open_config(), "loading config");
+    try_box_context!(parse_config(), "parsing config");
+
+    Ok(())
+}
+
+fn main() {
+    match load() {
+        Ok(_) => println!("Success!"),
+        Err(err) => {
+            // Recover the concrete error type if the caller cares which one it was
+            if err.is::<ConfigError>() {
+                println!("config was invalid");
+            }
+
+            // Or just print the whole thing as structured JSON for a log pipeline
+            println!("{}", err.format::<JsonBacktraceFmt>(true, false));
+        }
+    }
+}
@@ -2,15 +2,97 @@
 //!
 //! This module defines a formatting API for formatting both inline and captured backtraces,
 //! and a structure for holding file and line level captured backtraces.
-
+//!
+//! Capturing a backtrace on every `throw!`/`trace_error!` can be expensive, so whether
+//! `SourceBacktrace::new` actually walks the stack is controlled a few ways: the `backtrace`
+//! Cargo feature (on by default) compiles the capture out entirely when disabled; by default
+//! it only walks the stack in debug/test builds (`cfg!(debug_assertions)`), leaving release
+//! and bench builds free; and the `TRACE_ERROR_BACKTRACE` environment variable (falling back
+//! to `RUST_BACKTRACE`) overrides that default in either direction without recompiling. Use
+//! `SourceBacktrace::none` to opt out unconditionally.
+
+#[cfg(feature = "backtrace")]
 use std::os::raw::c_void;
 use std::fmt::{Debug, Formatter, Result as FmtResult};
+#[cfg(feature = "backtrace")]
 use std::path::Path;
 use std::thread;
+#[cfg(feature = "backtrace")]
 use std::mem;
+#[cfg(feature = "backtrace")]
+use std::env;
+#[cfg(feature = "backtrace")]
+use std::sync::atomic::{AtomicUsize, Ordering};
 
+#[cfg(feature = "backtrace")]
 use bt::{resolve, trace, Backtrace, Symbol, SymbolName, BacktraceSymbol};
 
+use {format_context, ContextFrame};
+
+/// Stand-in for `backtrace::Symbol` when the `backtrace` feature is disabled.
+///
+/// Uninhabited, so `BacktraceFmt::format` can never actually be called with one; this only
+/// exists to keep the trait's signature unchanged regardless of the feature.
+#[cfg(not(feature = "backtrace"))]
+pub enum Symbol {}
+
+/// Stand-in for `backtrace::BacktraceSymbol` when the `backtrace` feature is disabled.
+#[cfg(not(feature = "backtrace"))]
+pub enum BacktraceSymbol {}
+
+/// Stand-in for `backtrace::SymbolName` when the `backtrace` feature is disabled.
+#[cfg(not(feature = "backtrace"))]
+pub enum SymbolName {}
+
+/// The concrete backtrace type stored in a `SourceBacktrace`.
+///
+/// When the `backtrace` feature is disabled, this is a zero-sized shim so that
+/// `SourceBacktrace::new` never pulls in stack-walking machinery, mirroring the
+/// compatibility-shim approach used by `error_support`.
+#[cfg(feature = "backtrace")]
+type CapturedBacktrace = Backtrace;
+
+#[cfg(not(feature = "backtrace"))]
+#[derive(Clone, Debug)]
+struct CapturedBacktrace;
+
+#[cfg(feature = "backtrace")]
+const STATE_UNINIT: usize = 0;
+#[cfg(feature = "backtrace")]
+const STATE_DISABLED: usize = 1;
+#[cfg(feature = "backtrace")]
+const STATE_ENABLED: usize = 2;
+
+#[cfg(feature = "backtrace")]
+static BACKTRACE_STATE: AtomicUsize = AtomicUsize::new(STATE_UNINIT);
+
+/// Checks, and caches, whether runtime backtrace capture is enabled.
+///
+/// Reads `TRACE_ERROR_BACKTRACE` (falling back to `RUST_BACKTRACE`) exactly once per
+/// process. An explicit `"0"` disables capture and anything else explicit enables it,
+/// overriding the build-profile default; with neither variable set, capture defaults
+/// to on in debug/test builds and off in optimized builds, so `throw!`/`trace_error!`
+/// can be sprinkled everywhere without worrying about release overhead.
+#[cfg(feature = "backtrace")]
+fn backtrace_enabled() -> bool {
+    match BACKTRACE_STATE.load(Ordering::Relaxed) {
+        STATE_DISABLED => false,
+        STATE_ENABLED => true,
+        _ => {
+            let override_ = env::var("TRACE_ERROR_BACKTRACE")
+                .or_else(|_| env::var("RUST_BACKTRACE"))
+                .ok()
+                .map(|value| value != "0");
+
+            let enabled = override_.unwrap_or_else(|| cfg!(debug_assertions));
+
+            BACKTRACE_STATE.store(if enabled { STATE_ENABLED } else { STATE_DISABLED }, Ordering::Relaxed);
+
+            enabled
+        }
+    }
+}
+
 /// Trait to define formatting for backtrace symbols
 pub trait BacktraceFmt {
     /// Formats backtrace symbol components in some way
@@ -18,6 +100,73 @@ pub trait BacktraceFmt {
 
     /// Same as `BacktraceFmt::format`, but accepts a captured `BacktraceSymbol` instead
     fn format_captured(count: u32, symbol: &BacktraceSymbol) -> String;
+
+    /// Emitted once before any frames, only when the caller asked for a header. `captured`
+    /// is `false` when no backtrace was actually walked (e.g. `SourceBacktrace::none`, or the
+    /// `backtrace` feature disabled), so formatters can surface that as structured data
+    /// instead of it only being implied by `frames` being empty.
+    ///
+    /// Defaults to nothing; formatters that need a prologue (e.g. `JsonBacktraceFmt` opening
+    /// its wrapping object) can override this instead of hand-rolling it at each call site.
+    #[inline]
+    #[allow(unused_variables)]
+    fn header(thread: &str, line: u32, file: &str, captured: bool) -> String {
+        String::new()
+    }
+
+    /// Emitted between each formatted frame. Defaults to nothing.
+    #[inline]
+    fn separator() -> String {
+        String::new()
+    }
+
+    /// Emitted once after all frames, only when the caller asked for a header. Defaults to nothing.
+    #[inline]
+    fn footer() -> String {
+        String::new()
+    }
+
+    /// Emitted once before any frames, regardless of whether a header was requested.
+    ///
+    /// Defaults to nothing. Formatters whose frame list must stay self-contained even when
+    /// `header: false` is passed (e.g. `JsonBacktraceFmt` wrapping frames in a JSON array)
+    /// override this instead of `header`, since `header` is only emitted conditionally.
+    #[inline]
+    fn open() -> String {
+        String::new()
+    }
+
+    /// Emitted once after all frames, regardless of whether a header was requested. Defaults to nothing.
+    #[inline]
+    fn close() -> String {
+        String::new()
+    }
+
+    /// Emitted in place of frames when no backtrace was captured (e.g. via
+    /// `SourceBacktrace::none`, or with the `backtrace` feature disabled).
+    ///
+    /// Defaults to the plain-text placeholder used by `DefaultBacktraceFmt`; formatters whose
+    /// frames are a uniform array of same-shaped records (e.g. `JsonBacktraceFmt`) should
+    /// override this to emit nothing and signal the absence via `header`'s `captured` flag
+    /// instead, so `frames` never contains an element shaped differently from the rest.
+    #[inline]
+    #[allow(unused_variables)]
+    fn no_backtrace(line: u32, file: &str) -> String {
+        format!("<no backtrace captured, at {}:{}>\n", file, line)
+    }
+
+    /// Combine the error's `Display` output, any attached context frames (outermost first),
+    /// and the already-formatted backtrace into the final record that `Trace`/`BoxedTrace`
+    /// render as their `Display`/`format` output.
+    ///
+    /// Defaults to the plain-text layout: the error, then "while ..., at file:line" context
+    /// lines, then the formatted backtrace. Formatters that produce a structured backtrace
+    /// (e.g. `JsonBacktraceFmt`) override this so the error and context are embedded as part
+    /// of the same structured document instead of being string-pasted in front of it.
+    #[inline]
+    fn compose(error: &str, context: &[ContextFrame], backtrace: String) -> String {
+        format!("{}\n{}{}", error, format_context(context), backtrace)
+    }
 }
 
 /// Default backtrace formatter that tries to resemble rustc panic backtraces somewhat
@@ -43,6 +192,7 @@ pub trait BacktraceFmt {
 /// ```
 pub struct DefaultBacktraceFmt;
 
+#[cfg(feature = "backtrace")]
 impl DefaultBacktraceFmt {
     fn real_format(count: u32,
                    name: Option<SymbolName>,
@@ -72,34 +222,177 @@ impl DefaultBacktraceFmt {
 }
 
 impl BacktraceFmt for DefaultBacktraceFmt {
+    #[cfg(feature = "backtrace")]
     #[inline]
     fn format(count: u32, symbol: &Symbol) -> String {
         DefaultBacktraceFmt::real_format(count, symbol.name(), symbol.addr(), symbol.filename(), symbol.lineno())
     }
 
+    #[cfg(not(feature = "backtrace"))]
+    #[inline]
+    fn format(_count: u32, symbol: &Symbol) -> String {
+        match *symbol {}
+    }
+
+    #[cfg(feature = "backtrace")]
     #[inline]
     fn format_captured(count: u32, symbol: &BacktraceSymbol) -> String {
         // Could just use format!("{:?}", symbol) since BacktraceSymbol has a debug format specifier, but eh, I like mine better
         DefaultBacktraceFmt::real_format(count, symbol.name(), symbol.addr(), symbol.filename(), symbol.lineno())
     }
+
+    #[cfg(not(feature = "backtrace"))]
+    #[inline]
+    fn format_captured(_count: u32, symbol: &BacktraceSymbol) -> String {
+        match *symbol {}
+    }
+
+    #[inline]
+    #[allow(unused_variables)]
+    fn header(thread: &str, line: u32, file: &str, captured: bool) -> String {
+        format!("Stack backtrace for task \"<{}>\" at line {} of \"{}\":\n", thread, line, file)
+    }
+}
+
+/// Backtrace formatter that emits well-formed JSON, for log pipelines that want to ingest
+/// traces as structured data instead of scraping rustc-style text.
+///
+/// Each frame is an object with `index`, `address`, `symbol`, `file`, and `line` fields, so
+/// `frames` is always a uniform array of same-shaped records — even when no backtrace was
+/// captured, in which case `frames` is simply empty rather than gaining a differently-shaped
+/// placeholder element. With `header: false`, the frames are wrapped in a JSON array (`[...]`)
+/// on their own. With `header: true`, that array is additionally wrapped in an object alongside
+/// `thread`, `file`, `line`, and `captured` fields taken from the originating
+/// `SourceBacktrace`/call site; `captured` is `false` when `frames` is empty because no
+/// backtrace was actually walked, so consumers can distinguish that from a real backtrace that
+/// simply resolved no frames. Either way the result is valid, self-contained JSON; `header`
+/// only controls whether that extra context is included, not whether the output parses.
+/// `Trace`/`BoxedTrace::format` additionally embed this backtrace, the error, and any context
+/// frames as fields of one JSON object via `BacktraceFmt::compose`.
+pub struct JsonBacktraceFmt;
+
+#[cfg(feature = "backtrace")]
+impl JsonBacktraceFmt {
+    fn real_format(count: u32,
+                   name: Option<SymbolName>,
+                   addr: Option<*mut c_void>,
+                   filename: Option<&Path>,
+                   lineno: Option<u32>) -> String {
+        let name = name.and_then(|name| { name.as_str() }).unwrap_or("<unknown>");
+        let filename = filename.map(|filename| filename.display().to_string()).unwrap_or_default();
+
+        format!("{{\"index\":{},\"address\":\"{:p}\",\"symbol\":\"{}\",\"file\":\"{}\",\"line\":{}}}",
+                count, addr.unwrap_or(0x0 as *mut _), json_escape(name), json_escape(&filename), lineno.unwrap_or(0))
+    }
+}
+
+impl BacktraceFmt for JsonBacktraceFmt {
+    #[cfg(feature = "backtrace")]
+    #[inline]
+    fn format(count: u32, symbol: &Symbol) -> String {
+        JsonBacktraceFmt::real_format(count, symbol.name(), symbol.addr(), symbol.filename(), symbol.lineno())
+    }
+
+    #[cfg(not(feature = "backtrace"))]
+    #[inline]
+    fn format(_count: u32, symbol: &Symbol) -> String {
+        match *symbol {}
+    }
+
+    #[cfg(feature = "backtrace")]
+    #[inline]
+    fn format_captured(count: u32, symbol: &BacktraceSymbol) -> String {
+        JsonBacktraceFmt::real_format(count, symbol.name(), symbol.addr(), symbol.filename(), symbol.lineno())
+    }
+
+    #[cfg(not(feature = "backtrace"))]
+    #[inline]
+    fn format_captured(_count: u32, symbol: &BacktraceSymbol) -> String {
+        match *symbol {}
+    }
+
+    #[inline]
+    fn header(thread: &str, line: u32, file: &str, captured: bool) -> String {
+        format!("{{\"thread\":\"{}\",\"file\":\"{}\",\"line\":{},\"captured\":{},\"frames\":",
+                json_escape(thread), json_escape(file), line, captured)
+    }
+
+    #[inline]
+    fn separator() -> String {
+        ",".to_owned()
+    }
+
+    #[inline]
+    fn footer() -> String {
+        "}".to_owned()
+    }
+
+    #[inline]
+    fn open() -> String {
+        "[".to_owned()
+    }
+
+    #[inline]
+    fn close() -> String {
+        "]".to_owned()
+    }
+
+    #[inline]
+    #[allow(unused_variables)]
+    fn no_backtrace(line: u32, file: &str) -> String {
+        // The absence is already signaled by header()'s captured:false; frames stays
+        // an empty, uniformly-shaped array instead of gaining a differently-shaped element.
+        String::new()
+    }
+
+    fn compose(error: &str, context: &[ContextFrame], backtrace: String) -> String {
+        let mut rendered_context = String::new();
+
+        for (i, &(ref message, line, file)) in context.iter().rev().enumerate() {
+            if i > 0 {
+                rendered_context.push(',');
+            }
+
+            rendered_context += &format!("{{\"message\":\"{}\",\"file\":\"{}\",\"line\":{}}}",
+                                          json_escape(message), json_escape(file), line);
+        }
+
+        format!("{{\"error\":\"{}\",\"context\":[{}],\"backtrace\":{}}}",
+                json_escape(error), rendered_context, backtrace)
+    }
+}
+
+/// Escapes the characters JSON requires escaping in a string value; callers are expected to
+/// wrap the result in their own surrounding quotes.
+fn json_escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
 }
 
 /// Generates a formatted backtrace (via `Fmt` type) from here, but expects `line` and `file` to be where it was called from.
 ///
 /// The actual call to `format_trace` and `trace` are ignored.
+#[cfg(feature = "backtrace")]
 #[inline(never)]
 pub fn format_trace<Fmt: BacktraceFmt>(header: bool, line: u32, file: &str) -> String {
     // Ignore `format_trace` and `backtrace::trace` calls, both of which are marked as #[inline(never)],
     // so they will always show up.
     const IGNORE_COUNT: u32 = 2;
 
-    let mut traces = if header {
-        format!("Stack backtrace for task \"<{}>\" at line {} of \"{}\":\n",
-                thread::current().name().unwrap_or("unnamed"), line, file)
-    } else {
-        String::new()
-    };
-
+    let mut frames = Vec::new();
     let mut count = 0;
 
     trace(|frame| {
@@ -109,7 +402,7 @@ pub fn format_trace<Fmt: BacktraceFmt>(header: bool, line: u32, file: &str) -> S
             let before = count;
 
             resolve(frame.ip(), |symbol| {
-                traces += Fmt::format(count - IGNORE_COUNT, &symbol).as_str();
+                frames.push(Fmt::format(count - IGNORE_COUNT, &symbol));
 
                 count += 1;
             });
@@ -118,7 +411,7 @@ pub fn format_trace<Fmt: BacktraceFmt>(header: bool, line: u32, file: &str) -> S
             if count == before {
                 // If `symbol_address` doesn't work, oh well.
                 resolve(frame.symbol_address(), |symbol| {
-                    traces += Fmt::format(count - IGNORE_COUNT, &symbol).as_str();
+                    frames.push(Fmt::format(count - IGNORE_COUNT, &symbol));
 
                     count += 1;
                 });
@@ -129,15 +422,57 @@ pub fn format_trace<Fmt: BacktraceFmt>(header: bool, line: u32, file: &str) -> S
         true
     });
 
+    let mut traces = if header {
+        Fmt::header(thread::current().name().unwrap_or("unnamed"), line, file, true)
+    } else {
+        String::new()
+    };
+
+    traces += &Fmt::open();
+    traces += &frames.join(&Fmt::separator());
+    traces += &Fmt::close();
+
+    if header {
+        traces += &Fmt::footer();
+    }
+
+    traces
+}
+
+/// Generates a formatted backtrace (via `Fmt` type) from here, but expects `line` and `file` to be where it was called from.
+///
+/// The `backtrace` feature is disabled, so this always degrades gracefully to `Fmt::no_backtrace`
+/// in place of frames, same as an uncaptured `SourceBacktrace`.
+#[cfg(not(feature = "backtrace"))]
+pub fn format_trace<Fmt: BacktraceFmt>(header: bool, line: u32, file: &str) -> String {
+    let mut traces = if header {
+        Fmt::header(thread::current().name().unwrap_or("unnamed"), line, file, false)
+    } else {
+        String::new()
+    };
+
+    traces += &Fmt::open();
+    traces += &Fmt::no_backtrace(line, file);
+    traces += &Fmt::close();
+
+    if header {
+        traces += &Fmt::footer();
+    }
+
     traces
 }
 
 /// Backtrace that also contains the exact line and file in which it originated from.
 ///
-/// Usually created in a macro using the `line!()` and `file!()` macros
+/// Usually created in a macro using the `line!()` and `file!()` macros.
+///
+/// The captured backtrace itself is optional: `SourceBacktrace::none` never captures one, and
+/// `SourceBacktrace::new` only does when the `backtrace` feature is enabled AND runtime capture
+/// is on (by default only in debug/test builds, overridable via `TRACE_ERROR_BACKTRACE`/
+/// `RUST_BACKTRACE`).
 #[derive(Clone)]
 pub struct SourceBacktrace {
-    backtrace: Backtrace,
+    backtrace: Option<CapturedBacktrace>,
     line: u32,
     file: &'static str,
 }
@@ -149,19 +484,51 @@ impl Debug for SourceBacktrace {
 }
 
 impl SourceBacktrace {
-    /// Create a new `SourceBacktrace` if you know the line and file
+    /// Create a new `SourceBacktrace` if you know the line and file.
+    ///
+    /// Whether this actually walks the stack depends on the `backtrace` feature and the
+    /// `TRACE_ERROR_BACKTRACE`/`RUST_BACKTRACE` environment variables; see the module docs.
+    #[cfg(feature = "backtrace")]
+    pub fn new(line: u32, file: &'static str) -> SourceBacktrace {
+        let backtrace = if backtrace_enabled() {
+            Some(Backtrace::new())
+        } else {
+            None
+        };
+
+        SourceBacktrace {
+            backtrace: backtrace,
+            line: line,
+            file: file,
+        }
+    }
+
+    /// Create a new `SourceBacktrace` if you know the line and file.
+    ///
+    /// The `backtrace` feature is disabled, so this always behaves like `SourceBacktrace::none`.
+    #[cfg(not(feature = "backtrace"))]
     pub fn new(line: u32, file: &'static str) -> SourceBacktrace {
+        SourceBacktrace::none(line, file)
+    }
+
+    /// Create a new `SourceBacktrace` that never captures a backtrace.
+    ///
+    /// This is the cheapest possible constructor: no stack walk, no allocation. Useful for
+    /// error paths that are known to be hot and never inspected for their trace.
+    #[inline]
+    pub fn none(line: u32, file: &'static str) -> SourceBacktrace {
         SourceBacktrace {
-            backtrace: Backtrace::new(),
+            backtrace: None,
             line: line,
             file: file,
         }
     }
 
-    /// Get a reference to the raw `Backtrace` instance
+    /// Get a reference to the raw `Backtrace` instance, if one was captured
+    #[cfg(feature = "backtrace")]
     #[inline]
-    pub fn raw(&self) -> &Backtrace {
-        &self.backtrace
+    pub fn raw(&self) -> Option<&Backtrace> {
+        self.backtrace.as_ref()
     }
 
     /// Get the line at which this backtrace originated from
@@ -177,23 +544,43 @@ impl SourceBacktrace {
     }
 
     /// Format this backtrace with the given formatter and the given options
+    ///
+    /// If no backtrace was captured (e.g. an optimized build without the runtime override),
+    /// this degrades gracefully to `Fmt::no_backtrace` in place of frames, which for
+    /// `DefaultBacktraceFmt` is just the recorded `file`/`line`.
+    #[cfg(feature = "backtrace")]
     pub fn format<Fmt: BacktraceFmt>(&self, header: bool, reverse: bool) -> String {
         // Ignore `backtrace::trace` call
         const IGNORE_COUNT: u32 = 1;
 
-        let mut traces = if header {
-            format!("Stack backtrace for task \"<{}>\" at line {} of \"{}\":\n",
-                    thread::current().name().unwrap_or("unnamed"), self.line, self.file)
-        } else {
-            String::new()
+        let backtrace = match self.backtrace {
+            Some(ref backtrace) => backtrace,
+            None => {
+                let mut traces = if header {
+                    Fmt::header(thread::current().name().unwrap_or("unnamed"), self.line, self.file, false)
+                } else {
+                    String::new()
+                };
+
+                traces += &Fmt::open();
+                traces += &Fmt::no_backtrace(self.line, self.file);
+                traces += &Fmt::close();
+
+                if header {
+                    traces += &Fmt::footer();
+                }
+
+                return traces;
+            }
         };
 
+        let mut frames = Vec::new();
         let mut count = 0;
 
         if reverse {
             let mut symbols = Vec::new();
 
-            for frame in self.backtrace.frames() {
+            for frame in backtrace.frames() {
                 for symbol in frame.symbols() {
                     symbols.push(symbol);
                 }
@@ -211,13 +598,13 @@ impl SourceBacktrace {
                         }
                     }
 
-                    traces += Fmt::format_captured(count - IGNORE_COUNT, symbol).as_str();
+                    frames.push(Fmt::format_captured(count - IGNORE_COUNT, symbol));
                 }
 
                 count += 1;
             }
         } else {
-            for frame in self.backtrace.frames() {
+            for frame in backtrace.frames() {
                 for symbol in frame.symbols() {
                     if count >= IGNORE_COUNT {
                         if let Some(name) = symbol.name() {
@@ -230,7 +617,7 @@ impl SourceBacktrace {
                             }
                         }
 
-                        traces += Fmt::format_captured(count - IGNORE_COUNT, symbol).as_str();
+                        frames.push(Fmt::format_captured(count - IGNORE_COUNT, symbol));
                     }
 
                     count += 1;
@@ -238,13 +625,52 @@ impl SourceBacktrace {
             }
         }
 
+        let mut traces = if header {
+            Fmt::header(thread::current().name().unwrap_or("unnamed"), self.line, self.file, true)
+        } else {
+            String::new()
+        };
+
+        traces += &Fmt::open();
+        traces += &frames.join(&Fmt::separator());
+        traces += &Fmt::close();
+
+        if header {
+            traces += &Fmt::footer();
+        }
+
+        traces
+    }
+
+    /// Format this backtrace with the given formatter and the given options
+    ///
+    /// The `backtrace` feature is disabled, so this always degrades gracefully to
+    /// `Fmt::no_backtrace` in place of frames, which for `DefaultBacktraceFmt` is just the
+    /// recorded `file`/`line`.
+    #[cfg(not(feature = "backtrace"))]
+    pub fn format<Fmt: BacktraceFmt>(&self, header: bool, _reverse: bool) -> String {
+        let mut traces = if header {
+            Fmt::header(thread::current().name().unwrap_or("unnamed"), self.line, self.file, false)
+        } else {
+            String::new()
+        };
+
+        traces += &Fmt::open();
+        traces += &Fmt::no_backtrace(self.line, self.file);
+        traces += &Fmt::close();
+
+        if header {
+            traces += &Fmt::footer();
+        }
+
         traces
     }
 }
 
+#[cfg(feature = "backtrace")]
 impl From<Backtrace> for SourceBacktrace {
     fn from(backtrace: Backtrace) -> SourceBacktrace {
-        SourceBacktrace { line: line!(), file: file!(), backtrace: backtrace }
+        SourceBacktrace { line: line!(), file: file!(), backtrace: Some(backtrace) }
     }
 }
 
@@ -272,4 +698,62 @@ macro_rules! backtrace_noheader {
     ($fmt:ty) => {
         $crate::backtrace::format_trace::<$fmt>(false, line!(), file!())
     };
+}
+
+#[cfg(test)]
+mod tests {
+    use backtrace::{json_escape, BacktraceFmt, DefaultBacktraceFmt, JsonBacktraceFmt, SourceBacktrace};
+
+    #[test]
+    fn json_escape_handles_quotes_and_backslashes() {
+        assert_eq!(json_escape(r#"say "hi" \ bye"#), r#"say \"hi\" \\ bye"#);
+    }
+
+    #[test]
+    fn json_escape_handles_named_control_characters() {
+        assert_eq!(json_escape("a\nb\rc\td"), r"a\nb\rc\td");
+    }
+
+    #[test]
+    fn json_escape_handles_other_control_characters() {
+        let input = format!("a{}b{}c", '\u{0}', '\u{1f}');
+        assert_eq!(json_escape(&input), "a\\u0000b\\u001fc");
+    }
+
+    #[test]
+    fn json_escape_leaves_ordinary_text_untouched() {
+        assert_eq!(json_escape("hello, world"), "hello, world");
+    }
+
+    #[test]
+    fn compose_renders_context_outermost_first() {
+        let context = vec![
+            ("first attached".to_owned(), 1, "a.rs"),
+            ("second attached".to_owned(), 2, "b.rs"),
+        ];
+
+        let rendered = JsonBacktraceFmt::compose("boom", &context, "[]".to_owned());
+
+        let first = rendered.find("second attached").expect("second attached present");
+        let second = rendered.find("first attached").expect("first attached present");
+
+        assert!(first < second, "outermost (most recently attached) context should render first");
+    }
+
+    #[test]
+    fn none_formats_the_no_backtrace_placeholder() {
+        let backtrace = SourceBacktrace::none(7, "foo.rs");
+
+        let rendered = backtrace.format::<DefaultBacktraceFmt>(false, false);
+
+        assert_eq!(rendered, "<no backtrace captured, at foo.rs:7>\n");
+    }
+
+    #[cfg(feature = "backtrace")]
+    #[test]
+    fn none_never_populates_the_captured_backtrace() {
+        let backtrace = SourceBacktrace::none(1, "foo.rs");
+
+        assert!(backtrace.raw().is_none());
+    }
 }
\ No newline at end of file
@@ -0,0 +1,246 @@
+//! Type-erased variant of `Trace` for aggregating heterogeneous error types.
+//!
+//! `Trace<E>` ties a backtrace to one concrete error type, so a function can only ever
+//! return one kind of error. `BoxedTrace` instead boxes the error as a trait object, so a
+//! function can return a single `Result<T, BoxedTrace>` even when its various failure
+//! paths produce unrelated error types, the way `anyhow::Error` aggregates causes.
+
+use std::error::Error;
+use std::fmt::{Debug, Display, Formatter, Result as FmtResult};
+
+use backtrace::{BacktraceFmt, DefaultBacktraceFmt, SourceBacktrace};
+use {ContextFrame, Trace};
+
+/// Type-erased error paired with the backtrace captured at its throw site.
+///
+/// Unlike `Trace<E>`, `BoxedTrace` can hold any `Error + Send + 'static`, so it can be used
+/// as the error type of a `Result` that aggregates several unrelated error types. Use
+/// `is`, `downcast_ref`, `downcast_mut`, or `downcast` to recover the original concrete type.
+pub struct BoxedTrace {
+    error: Box<dyn Error + Send + 'static>,
+    backtrace: Box<SourceBacktrace>,
+    context: Vec<ContextFrame>,
+}
+
+impl BoxedTrace {
+    /// Creates a new `BoxedTrace` from the given error and backtrace
+    #[inline]
+    pub fn new<E: Error + Send + 'static>(error: E, backtrace: Box<SourceBacktrace>) -> BoxedTrace {
+        BoxedTrace { error: Box::new(error), backtrace: backtrace, context: Vec::new() }
+    }
+
+    /// Get a reference to the inner backtrace
+    #[inline]
+    pub fn backtrace(&self) -> &SourceBacktrace {
+        &*self.backtrace
+    }
+
+    /// Get a reference to the inner error as a trait object
+    #[inline]
+    pub fn error(&self) -> &(dyn Error + Send + 'static) {
+        &*self.error
+    }
+
+    /// Get the context messages attached so far, outermost (most recently attached) last
+    #[inline]
+    pub fn context(&self) -> &[ContextFrame] {
+        &self.context
+    }
+
+    /// Attach a context message and the call site it was attached at, returning `self` so
+    /// propagating code can chain straight off of an error value.
+    ///
+    /// Usually invoked through `try_box_context!` rather than called directly, so that `line`
+    /// and `file` are captured at the call site automatically.
+    #[inline]
+    pub fn with_context<S: Into<String>>(mut self, message: S, line: u32, file: &'static str) -> BoxedTrace {
+        self.context.push((message.into(), line, file));
+        self
+    }
+
+    /// Format the error, any attached context messages (outermost first), and the backtrace
+    ///
+    /// The exact composition (plain text vs. a single JSON document, etc.) is up to `Fmt`;
+    /// see `BacktraceFmt::compose`.
+    pub fn format<Fmt: BacktraceFmt>(&self, header: bool, reverse: bool) -> String {
+        Fmt::compose(&self.error.to_string(), &self.context, self.backtrace.format::<Fmt>(header, reverse))
+    }
+
+    /// Returns `true` if the inner error is of type `E`
+    #[inline]
+    pub fn is<E: Error + 'static>(&self) -> bool {
+        self.error.is::<E>()
+    }
+
+    /// Attempt to downcast the inner error to a reference of type `E`
+    #[inline]
+    pub fn downcast_ref<E: Error + 'static>(&self) -> Option<&E> {
+        self.error.downcast_ref::<E>()
+    }
+
+    /// Attempt to downcast the inner error to a mutable reference of type `E`
+    #[inline]
+    pub fn downcast_mut<E: Error + 'static>(&mut self) -> Option<&mut E> {
+        self.error.downcast_mut::<E>()
+    }
+
+    /// Attempt to downcast the inner error to an owned value of type `E`, recovering the
+    /// backtrace and context on success and returning `self` unchanged on failure
+    pub fn downcast<E: Error + 'static>(self) -> Result<Trace<E>, BoxedTrace> {
+        let BoxedTrace { error, backtrace, context } = self;
+
+        match error.downcast::<E>() {
+            Ok(error) => Ok(Trace::from_parts(*error, backtrace, context)),
+            Err(error) => Err(BoxedTrace { error: error, backtrace: backtrace, context: context }),
+        }
+    }
+}
+
+impl<E: Error + Send + 'static> From<Trace<E>> for BoxedTrace {
+    fn from(trace: Trace<E>) -> BoxedTrace {
+        BoxedTrace {
+            error: Box::new(trace.error),
+            backtrace: trace.backtrace,
+            context: trace.context,
+        }
+    }
+}
+
+impl Debug for BoxedTrace {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(f, "BoxedTrace {{ error: {:?}, backtrace: {:?}, context: {:?} }}", self.error, self.backtrace, self.context)
+    }
+}
+
+impl Display for BoxedTrace {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(f, "{}", self.format::<DefaultBacktraceFmt>(true, false))
+    }
+}
+
+impl Error for BoxedTrace {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&*self.error)
+    }
+}
+
+/// Like `try_throw!`, but targets `BoxedTrace` instead of a concrete `Trace<E>`, so the
+/// error value can be of any type implementing `Error + Send + 'static`.
+///
+/// Note that the backtrace will only go as far as the location this macro was invoked
+#[macro_export]
+macro_rules! try_box {
+    ($res:expr) => (match $res {
+        ::std::result::Result::Ok(val) => val,
+        ::std::result::Result::Err(err) => {
+            return ::std::result::Result::Err($crate::boxed::BoxedTrace::new(
+                err,
+                ::std::boxed::Box::new($crate::backtrace::SourceBacktrace::new(line!(), file!()))
+            ))
+        }
+    })
+}
+
+#[doc(hidden)]
+#[inline(always)]
+pub fn _assert_boxed_trace_result<T>(res: Result<T, BoxedTrace>) -> Result<T, BoxedTrace> {
+    res
+}
+
+/// Like `try_context!`, but targets `Result<T, BoxedTrace>` instead of `TraceResult<T, E>`,
+/// attaching a context message (tagged with this macro's call site) to the propagating
+/// `BoxedTrace` before returning.
+///
+/// This relies on the return type of the function to
+/// provide type inference for the `Result::Ok(T)` type.
+#[macro_export]
+macro_rules! try_box_context {
+    ($res:expr, $msg:expr) => (match $crate::boxed::_assert_boxed_trace_result($res) {
+        ::std::result::Result::Ok(val) => val,
+        ::std::result::Result::Err(err) => {
+            return ::std::result::Result::Err(err.with_context($msg, line!(), file!()))
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fmt::{self, Display, Formatter};
+    use std::error::Error;
+
+    use backtrace::SourceBacktrace;
+    use boxed::BoxedTrace;
+
+    #[derive(Debug)]
+    struct FirstError;
+
+    impl Display for FirstError {
+        fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+            write!(f, "first error")
+        }
+    }
+
+    impl Error for FirstError {
+        fn description(&self) -> &str {
+            "first error"
+        }
+    }
+
+    #[derive(Debug)]
+    struct SecondError;
+
+    impl Display for SecondError {
+        fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+            write!(f, "second error")
+        }
+    }
+
+    impl Error for SecondError {
+        fn description(&self) -> &str {
+            "second error"
+        }
+    }
+
+    fn boxed(error: FirstError) -> BoxedTrace {
+        BoxedTrace::new(error, Box::new(SourceBacktrace::none(1, "boxed.rs")))
+    }
+
+    #[test]
+    fn is_and_downcast_ref_match_the_concrete_type() {
+        let trace = boxed(FirstError);
+
+        assert!(trace.is::<FirstError>());
+        assert!(!trace.is::<SecondError>());
+        assert!(trace.downcast_ref::<FirstError>().is_some());
+        assert!(trace.downcast_ref::<SecondError>().is_none());
+    }
+
+    #[test]
+    fn downcast_mut_allows_mutation_without_losing_identity() {
+        let mut trace = boxed(FirstError);
+
+        assert!(trace.downcast_mut::<FirstError>().is_some());
+        assert!(trace.downcast_mut::<SecondError>().is_none());
+        assert!(trace.is::<FirstError>());
+    }
+
+    #[test]
+    fn downcast_round_trip_preserves_context() {
+        let trace = boxed(FirstError).with_context("loading config", 10, "boxed.rs");
+
+        let recovered = trace.downcast::<FirstError>().expect("downcast to the original type");
+
+        assert_eq!(recovered.context().len(), 1);
+        assert_eq!(recovered.context()[0].0, "loading config");
+    }
+
+    #[test]
+    fn downcast_to_the_wrong_type_returns_self_unchanged() {
+        let trace = boxed(FirstError).with_context("loading config", 10, "boxed.rs");
+
+        let trace = trace.downcast::<SecondError>().expect_err("downcast to an unrelated type");
+
+        assert_eq!(trace.context().len(), 1);
+        assert!(trace.is::<FirstError>());
+    }
+}
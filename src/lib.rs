@@ -8,6 +8,10 @@
 //! `Trace` and `TraceResult` should usually be used in place of `Result` using the macros
 //! `throw!`, `try_throw!`, and `try_rethrow!`
 //!
+//! While propagating a `TraceResult` up through several call sites, `try_context!` can attach
+//! a message at each one (e.g. `try_context!(open_file(path), "opening config file")`), so the
+//! final printed error reads as a layered "while doing X, at file:line" chain.
+//!
 //! Although the `?` syntax was just introduced, `trace-error` is not yet compatible with it until the `Carrier` trait is stabilized. As a result,
 //! all instances of `try!` and `?` should be replaced with `try_throw!` if you intend to use this crate to its fullest. However, the `?` operator
 //! can be used for `Result<_, Trace<E>>` when the return value is also a `Result` using `Trace<E>`, just because `From` is implemented for types for itself.
@@ -90,9 +94,11 @@
 #![allow(unknown_lints, inline_always)]
 #![deny(missing_docs)]
 
+#[cfg(feature = "backtrace")]
 extern crate backtrace as bt;
 
 pub mod backtrace;
+pub mod boxed;
 
 use std::error::Error;
 use std::ops::Deref;
@@ -103,20 +109,36 @@ use backtrace::{BacktraceFmt, DefaultBacktraceFmt, SourceBacktrace};
 /// Alias to aid in usage with `Result`
 pub type TraceResult<T, E> = Result<T, Trace<E>>;
 
+/// A context message attached via `Trace::with_context`/`try_context!`, paired with the
+/// call site it was attached at: `(message, line, file)`.
+pub type ContextFrame = (String, u32, &'static str);
+
 /// Trace error that encapsulates a backtrace alongside an error value.
 ///
-/// Trace itself does not implement `Error`, so they cannot be nested.
+/// Trace implements `Error` itself (see below), with `source()` returning the wrapped error,
+/// so a `Trace<E>` can be nested inside another error and still be reached by anything that
+/// walks the standard cause chain.
 #[derive(Debug)]
 pub struct Trace<E: Error> {
     error: E,
     backtrace: Box<SourceBacktrace>,
+    context: Vec<ContextFrame>,
 }
 
 impl<E: Error> Trace<E> {
     /// Creates a new `Trace` from the given error and backtrace
     #[inline]
     pub fn new(error: E, backtrace: Box<SourceBacktrace>) -> Trace<E> {
-        Trace { error: error, backtrace: backtrace }
+        Trace { error: error, backtrace: backtrace, context: Vec::new() }
+    }
+
+    /// Creates a new `Trace` from all of its parts, preserving previously-attached context.
+    ///
+    /// Used by `BoxedTrace::downcast` to recover a typed `Trace` without losing context
+    /// attached while the error was erased.
+    #[inline]
+    pub(crate) fn from_parts(error: E, backtrace: Box<SourceBacktrace>, context: Vec<ContextFrame>) -> Trace<E> {
+        Trace { error: error, backtrace: backtrace, context: context }
     }
 
     /// Consume self and return the inner error value
@@ -131,9 +153,29 @@ impl<E: Error> Trace<E> {
         &*self.backtrace
     }
 
-    /// Format the error and backtrace
+    /// Get the context messages attached so far, outermost (most recently attached) last
+    #[inline]
+    pub fn context(&self) -> &[ContextFrame] {
+        &self.context
+    }
+
+    /// Attach a context message and the call site it was attached at, returning `self` so
+    /// propagating code can chain straight off of an error value.
+    ///
+    /// Usually invoked through `try_context!` rather than called directly, so that `line`
+    /// and `file` are captured at the call site automatically.
+    #[inline]
+    pub fn with_context<S: Into<String>>(mut self, message: S, line: u32, file: &'static str) -> Trace<E> {
+        self.context.push((message.into(), line, file));
+        self
+    }
+
+    /// Format the error, any attached context messages (outermost first), and the backtrace
+    ///
+    /// The exact composition (plain text vs. a single JSON document, etc.) is up to `Fmt`;
+    /// see `BacktraceFmt::compose`.
     pub fn format<Fmt: BacktraceFmt>(&self, header: bool, reverse: bool) -> String {
-        format!("{}\n{}", self.error, self.backtrace.format::<Fmt>(header, reverse))
+        Fmt::compose(&self.error.to_string(), &self.context, self.backtrace.format::<Fmt>(header, reverse))
     }
 
     /// Convert the inner error of type `E` into type `O`
@@ -141,9 +183,31 @@ impl<E: Error> Trace<E> {
     pub fn convert<O: Error>(self) -> Trace<O> where O: From<E> {
         Trace {
             error: From::from(self.error),
-            backtrace: self.backtrace
+            backtrace: self.backtrace,
+            context: self.context,
         }
     }
+
+    /// Returns a `Display`-able view of just the inner error, omitting the backtrace.
+    ///
+    /// Useful when this `Trace` is reached through another error's `source()` chain: the
+    /// outer reporter already walked here, so printing the full `Trace` `Display` again
+    /// would duplicate backtrace frames.
+    #[inline]
+    pub fn terse(&self) -> Terse<E> {
+        Terse(self)
+    }
+}
+
+/// Renders context frames outermost-first, one "while doing X, at file:line" line each
+pub(crate) fn format_context(context: &[ContextFrame]) -> String {
+    let mut rendered = String::new();
+
+    for &(ref message, line, file) in context.iter().rev() {
+        rendered += &format!("while {}, at {}:{}\n", message, file, line);
+    }
+
+    rendered
 }
 
 unsafe impl<E: Error> Send for Trace<E> where E: Send {}
@@ -157,6 +221,23 @@ impl<E: Error> Deref for Trace<E> {
     }
 }
 
+impl<E: Error + 'static> Error for Trace<E> {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+/// Terse `Display` for a `Trace<E>`, printing only the inner error and omitting the backtrace.
+///
+/// Returned by `Trace::terse`.
+pub struct Terse<'a, E: Error + 'a>(&'a Trace<E>);
+
+impl<'a, E: Error> Display for Terse<'a, E> {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(f, "{}", self.0.error)
+    }
+}
+
 impl<E: Error> Display for Trace<E> {
     fn fmt(&self, f: &mut Formatter) -> FmtResult {
         write!(f, "{}", self.format::<DefaultBacktraceFmt>(true, false))
@@ -211,6 +292,21 @@ macro_rules! try_rethrow {
     })
 }
 
+/// Like `try_rethrow!`, but instead of converting the inner error type, attaches a context
+/// message (tagged with this macro's call site) to the propagating `Trace` before returning.
+///
+/// This relies on the return type of the function to
+/// provide type inference for the `Result::Ok(T)` type.
+#[macro_export]
+macro_rules! try_context {
+    ($res:expr, $msg:expr) => (match $crate::_assert_trace_result($res) {
+        ::std::result::Result::Ok(val) => val,
+        ::std::result::Result::Err(err) => {
+            return ::std::result::Result::Err(err.with_context($msg, line!(), file!()))
+        }
+    })
+}
+
 /// The core macro that creates the `Result::Err(Trace<E>)` value,
 /// but does not return it immediately.
 ///
@@ -232,3 +328,47 @@ macro_rules! trace_error {
         )))
     };
 }
+
+#[cfg(test)]
+mod tests {
+    use std::fmt::{self, Display, Formatter};
+    use std::error::Error;
+
+    use backtrace::SourceBacktrace;
+    use Trace;
+
+    #[derive(Debug)]
+    struct InnerError;
+
+    impl Display for InnerError {
+        fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+            write!(f, "inner error")
+        }
+    }
+
+    impl Error for InnerError {
+        fn description(&self) -> &str {
+            "inner error"
+        }
+    }
+
+    fn traced(error: InnerError) -> Trace<InnerError> {
+        Trace::new(error, Box::new(SourceBacktrace::none(1, "lib.rs")))
+    }
+
+    #[test]
+    fn source_returns_the_wrapped_error() {
+        let trace = traced(InnerError);
+
+        let source = trace.source().expect("source should be the wrapped error");
+        assert_eq!(source.to_string(), "inner error");
+    }
+
+    #[test]
+    fn terse_display_omits_the_backtrace() {
+        let trace = traced(InnerError);
+
+        assert_eq!(trace.terse().to_string(), "inner error");
+        assert_ne!(trace.terse().to_string(), trace.to_string());
+    }
+}